@@ -1,12 +1,19 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
 use once_cell::sync::OnceCell;
-use regex::Regex;
+use rayon::prelude::*;
+use regex::{Regex, RegexSet};
 use std::{
+    collections::HashSet,
     env,
     error::Error,
     fs, io,
-    iter::Sum,
     ops::Add,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
 };
 
 // == REGEX ==
@@ -18,85 +25,226 @@ use std::{
 //     memory: "512M"
 //     cpu: "500m"
 //
-// below are regex to parse all combinations
-static REGEX_REQ_CPU_MEM: OnceCell<Regex> = OnceCell::new();
-static REGEX_REQ_MEM_CPU: OnceCell<Regex> = OnceCell::new();
-static REGEX_LIM_CPU_MEM: OnceCell<Regex> = OnceCell::new();
-static REGEX_LIM_MEM_CPU: OnceCell<Regex> = OnceCell::new();
+// below are regex to parse all combinations, collapsed into a single
+// `RegexSet` pre-filter plus the capturing variants used once a file
+// is known to match.
+//
+// same four patterns, in the same order, so a `SetMatches` index
+// lines up with `CAPTURING_REGEXES[index]`. used to skip the capturing
+// passes entirely for files that match nothing.
+static REGEX_SET: OnceCell<RegexSet> = OnceCell::new();
+static CAPTURING_REGEXES: OnceCell<Vec<Regex>> = OnceCell::new();
+
+// same four patterns, in the same order, for both `REGEX_SET` and
+// `CAPTURING_REGEXES`. idempotent, so tests can call it freely.
+fn init_regexes() -> Result<(), Box<dyn Error>> {
+    // `CAPTURING_REGEXES` is set last, so once it is present both are ready
+    if CAPTURING_REGEXES.get().is_some() {
+        return Ok(());
+    }
 
-// resource syntax, e.g. 500m or 512Mi or 512M
-static REGEX_NUMERIC_PREFIX: OnceCell<Regex> = OnceCell::new();
+    let set_patterns = [
+        "requests:\\s*cpu:\\s*\"(.*)\"\\s*memory:\\s*(.*)",
+        "requests:\\s*memory:\\s*\"(.*)\"\\s*cpu:\\s*\"(.*)\"",
+        "limits:\\s*cpu:\\s*\"(.*)\"\\s*memory:\\s*(.*)",
+        "limits:\\s*memory:\\s*\"(.*)\"\\s*cpu:\\s*\"(.*)\"",
+    ];
+    let _ = REGEX_SET.set(RegexSet::new(&set_patterns)?);
+    let _ = CAPTURING_REGEXES.set(
+        set_patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()?,
+    );
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let dir = match env::args().skip(1).next() {
-        Some(dir) => PathBuf::from(dir),
+    Ok(())
+}
+
+// CLI inputs for a scan: the root directory, the flags that choose which
+// files under it get analyzed, and the number of worker threads.
+struct CliArgs {
+    dir: PathBuf,
+    no_ignore: bool,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    jobs: usize,
+}
+
+fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+fn parse_args() -> Result<CliArgs, Box<dyn Error>> {
+    let mut dir = None;
+    let mut no_ignore = false;
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    let mut jobs = default_jobs();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-ignore" => no_ignore = true,
+            "--include" => include.push(args.next().ok_or("--include requires a glob")?),
+            "--exclude" => exclude.push(args.next().ok_or("--exclude requires a glob")?),
+            "--jobs" => {
+                let value = args.next().ok_or("--jobs requires a number")?;
+                jobs = value
+                    .parse()
+                    .map_err(|_| format!("invalid --jobs value: {}", value))?;
+            }
+            _ => dir = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let dir = match dir {
+        Some(dir) => dir,
         None => env::current_dir()?,
     };
 
+    Ok(CliArgs {
+        dir,
+        no_ignore,
+        include,
+        exclude,
+        jobs,
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = parse_args()?;
+    let dir = args.dir.clone();
+
     println!("Analyzing kubernetes configs in {:?}", dir);
 
-    REGEX_REQ_CPU_MEM
-        .set(Regex::new(
-            "requests:\\s*cpu:\\s*\"(.*)\"\\s*memory:\\s*(.*)",
-        )?)
-        .unwrap();
-
-    REGEX_REQ_MEM_CPU
-        .set(Regex::new(
-            "requests:\\s*memory:\\s*\"(.*)\"\\s*cpu:\\s*\"(.*)\"",
-        )?)
-        .unwrap();
-
-    REGEX_LIM_CPU_MEM
-        .set(Regex::new(
-            "limits:\\s*cpu:\\s*\"(.*)\"\\s*memory:\\s*(.*)",
-        )?)
-        .unwrap();
-
-    REGEX_LIM_MEM_CPU
-        .set(Regex::new(
-            "limits:\\s*memory:\\s*\"(.*)\"\\s*cpu:\\s*\"(.*)\"",
-        )?)
-        .unwrap();
-
-    REGEX_NUMERIC_PREFIX.set(Regex::new("^([0-9]+)")?).unwrap();
-
-    let resources: Resources = find_yamls(&dir)?
-        .iter()
-        .map(|p| fs::read_to_string(p))
-        .filter_map(|r| r.ok())
-        .map(|c| analyze(&c))
-        .sum();
+    init_regexes()?;
+
+    let yamls = find_yamls(&dir, &args)?;
+    let total = yamls.len();
+    let processed = AtomicUsize::new(0);
+    // report roughly 20 times over the course of the scan, so small
+    // trees don't get spammed and large ones still show liveliness
+    let progress_every = (total / 20).max(1);
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()?;
+
+    // collect (not sum) inside the pool: collecting an IndexedParallelIterator
+    // preserves `yamls`' original order, so the final fold below always adds
+    // per-file results in the same sequence regardless of which thread
+    // finished first. summing directly with `.sum()` would reduce the `f32`
+    // cpu fields in a nondeterministic tree order, and float addition isn't
+    // associative, so totals could drift from run to run.
+    let per_file: Vec<Resources> = pool.install(|| {
+        yamls
+            .par_iter()
+            .map(|p| {
+                let resources = fs::read_to_string(p)
+                    .ok()
+                    .map(|c| analyze(&c))
+                    .unwrap_or_else(Resources::zero);
+
+                let done = processed.fetch_add(1, Ordering::Relaxed) + 1;
+                if done % progress_every == 0 || done == total {
+                    eprintln!("{}/{} files processed", done, total);
+                }
+
+                resources
+            })
+            .collect()
+    });
+
+    let resources: Resources = per_file.into_iter().fold(Resources::zero(), Add::add);
 
     println!("Total resources: {:?}", resources);
 
     Ok(())
 }
 
-fn find_yamls(root_dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+fn build_globset(globs: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        // matches ripgrep's globset behavior: `*` stops at `/` so a glob
+        // like `**/prod/**/*.yaml` doesn't over-match across directories.
+        builder.add(GlobBuilder::new(glob).literal_separator(true).build()?);
+    }
+    builder.build()
+}
+
+// walks `root_dir` looking for `.yaml`/`.yml` files, honoring `.gitignore`
+// and a project-level `.greedyignore` (same glob syntax) unless
+// `no_ignore` is set. directory symlinks are followed, with a guard
+// against cycles: each directory's canonicalized path is recorded the
+// first time it's visited, and a later visit to the same canonical path
+// is skipped rather than descended into.
+//
+// `args.include`/`args.exclude` further narrow the result to paths
+// (relative to `root_dir`) matching the given globs; an empty include
+// list means "all yaml/yml files", and exclude always wins over include.
+fn find_yamls(root_dir: &Path, args: &CliArgs) -> Result<Vec<PathBuf>, io::Error> {
     if !root_dir.is_dir() {
-        Ok(Vec::new())
-    } else {
-        let mut dirs_stack = vec![PathBuf::from(root_dir)];
-        let mut yamls = vec![];
-
-        while let Some(dir) = dirs_stack.pop() {
-            // traverse this dir, push all other dirs to stack, push all found yamls
-            for entry in fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_dir() {
-                    dirs_stack.push(path);
-                } else if let Some(ext) = path.extension() {
-                    if ext == "yaml" {
-                        yamls.push(path);
-                    }
-                }
-            }
+        return Ok(Vec::new());
+    }
+
+    let include =
+        build_globset(&args.include).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let exclude =
+        build_globset(&args.exclude).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+    let visited_dirs: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+
+    let mut builder = WalkBuilder::new(root_dir);
+    builder
+        .follow_links(true)
+        .hidden(false)
+        .git_ignore(!args.no_ignore)
+        .git_global(!args.no_ignore)
+        .git_exclude(!args.no_ignore)
+        .ignore(!args.no_ignore);
+    // `add_custom_ignore_filename` is tracked independently of the toggles
+    // above, so it must be skipped explicitly or `--no-ignore` would still
+    // honor `.greedyignore`.
+    if !args.no_ignore {
+        builder.add_custom_ignore_filename(".greedyignore");
+    }
+    builder.filter_entry(move |entry| {
+        if !entry.file_type().map_or(false, |ft| ft.is_dir()) {
+            return true;
+        }
+        match entry.path().canonicalize() {
+            Ok(canonical) => visited_dirs.lock().unwrap().insert(canonical),
+            Err(_) => true,
+        }
+    });
+
+    let mut yamls = vec![];
+    for result in builder.build() {
+        let entry = match result {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        if entry.file_type().map_or(false, |ft| ft.is_dir()) {
+            continue;
+        }
+
+        match entry.path().extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => {}
+            _ => continue,
+        }
+
+        let relative = entry.path().strip_prefix(root_dir).unwrap_or(entry.path());
+        if exclude.is_match(relative) || (!include.is_empty() && !include.is_match(relative)) {
+            continue;
         }
 
-        Ok(yamls)
+        yamls.push(entry.into_path());
     }
+
+    Ok(yamls)
 }
 
 #[derive(Debug)]
@@ -107,17 +255,14 @@ struct Resources {
     cpu_limit: f32,
 }
 
-impl Sum for Resources {
-    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-        iter.fold(
-            Resources {
-                mem_request: 0,
-                mem_limit: 0,
-                cpu_request: 0.0,
-                cpu_limit: 0.0,
-            },
-            Add::add,
-        )
+impl Resources {
+    fn zero() -> Self {
+        Resources {
+            mem_request: 0,
+            mem_limit: 0,
+            cpu_request: 0.0,
+            cpu_limit: 0.0,
+        }
     }
 }
 
@@ -140,24 +285,37 @@ fn analyze(config: &str) -> Resources {
     let mut mem_limits_str = Vec::new();
     let mut cpu_limits_str = Vec::new();
 
-    for cap in REGEX_REQ_CPU_MEM.get().unwrap().captures_iter(config) {
-        cpu_requests_str.push(String::from(&cap[1]));
-        mem_requests_str.push(String::from(&cap[2]));
-    }
-
-    for cap in REGEX_REQ_MEM_CPU.get().unwrap().captures_iter(config) {
-        mem_requests_str.push(String::from(&cap[1]));
-        cpu_requests_str.push(String::from(&cap[2]));
-    }
-
-    for cap in REGEX_LIM_CPU_MEM.get().unwrap().captures_iter(config) {
-        cpu_limits_str.push(String::from(&cap[1]));
-        mem_limits_str.push(String::from(&cap[2]));
-    }
+    let matches = REGEX_SET.get().unwrap().matches(config);
+    let capturing_regexes = CAPTURING_REGEXES.get().unwrap();
 
-    for cap in REGEX_LIM_MEM_CPU.get().unwrap().captures_iter(config) {
-        mem_limits_str.push(String::from(&cap[1]));
-        cpu_limits_str.push(String::from(&cap[2]));
+    for idx in matches.iter() {
+        match idx {
+            0 => {
+                for cap in capturing_regexes[0].captures_iter(config) {
+                    cpu_requests_str.push(String::from(&cap[1]));
+                    mem_requests_str.push(String::from(&cap[2]));
+                }
+            }
+            1 => {
+                for cap in capturing_regexes[1].captures_iter(config) {
+                    mem_requests_str.push(String::from(&cap[1]));
+                    cpu_requests_str.push(String::from(&cap[2]));
+                }
+            }
+            2 => {
+                for cap in capturing_regexes[2].captures_iter(config) {
+                    cpu_limits_str.push(String::from(&cap[1]));
+                    mem_limits_str.push(String::from(&cap[2]));
+                }
+            }
+            3 => {
+                for cap in capturing_regexes[3].captures_iter(config) {
+                    mem_limits_str.push(String::from(&cap[1]));
+                    cpu_limits_str.push(String::from(&cap[2]));
+                }
+            }
+            _ => unreachable!("RegexSet has exactly 4 patterns"),
+        }
     }
 
     Resources {
@@ -168,23 +326,150 @@ fn analyze(config: &str) -> Resources {
     }
 }
 
-fn num_prefix_or_zero(s: &str) -> u64 {
-    match REGEX_NUMERIC_PREFIX
-        .get()
-        .unwrap()
-        .captures_iter(s)
-        .map(|cap| cap[1].parse())
-        .next()
-    {
-        Some(Ok(v)) => v,
-        _ => 0,
-    }
+// splits a kubernetes quantity like "512Mi" or "1.5" into its decimal
+// mantissa and trailing unit suffix, e.g. ("512", "Mi") or ("1.5", "").
+fn split_mantissa_and_suffix(s: &str) -> (&str, &str) {
+    let split_at = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    s.split_at(split_at)
 }
 
+// parses a kubernetes memory quantity into bytes. supports decimal SI
+// suffixes (k, M, G, T, P, E; powers of 1000) and binary SI suffixes
+// (Ki, Mi, Gi, Ti, Pi, Ei; powers of 1024), plus a bare number meaning
+// bytes directly. an unknown suffix or unparsable mantissa is treated
+// as zero rather than silently truncated.
 fn parse_mem(s: &str) -> u64 {
-    num_prefix_or_zero(s)
+    let s = s.trim().trim_matches('"');
+    if s.is_empty() {
+        return 0;
+    }
+
+    let (mantissa, suffix) = split_mantissa_and_suffix(s);
+    let mantissa: f64 = match mantissa.parse() {
+        Ok(v) => v,
+        Err(_) => return 0,
+    };
+
+    let multiplier = match suffix {
+        "" => 1.0,
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "P" => 1e15,
+        "E" => 1e18,
+        "Ki" => 1024f64.powi(1),
+        "Mi" => 1024f64.powi(2),
+        "Gi" => 1024f64.powi(3),
+        "Ti" => 1024f64.powi(4),
+        "Pi" => 1024f64.powi(5),
+        "Ei" => 1024f64.powi(6),
+        _ => return 0,
+    };
+
+    (mantissa * multiplier).round() as u64
 }
 
+// parses a kubernetes CPU quantity into whole cores. a bare number
+// (possibly fractional, e.g. "0.5") means whole cores; an "m" suffix
+// means millicores (e.g. "500m" -> 0.5). an unknown suffix or
+// unparsable mantissa is treated as zero.
 fn parse_cpu(s: &str) -> f32 {
-    num_prefix_or_zero(s) as f32 / 1000.0
+    let s = s.trim().trim_matches('"');
+    if s.is_empty() {
+        return 0.0;
+    }
+
+    let (mantissa, suffix) = split_mantissa_and_suffix(s);
+    let mantissa: f64 = match mantissa.parse() {
+        Ok(v) => v,
+        Err(_) => return 0.0,
+    };
+
+    match suffix {
+        "" => mantissa as f32,
+        "m" => (mantissa / 1000.0) as f32,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mem_decimal_si_suffixes() {
+        assert_eq!(parse_mem("512"), 512);
+        assert_eq!(parse_mem("512k"), 512_000);
+        assert_eq!(parse_mem("512M"), 512_000_000);
+        assert_eq!(parse_mem("1G"), 1_000_000_000);
+    }
+
+    #[test]
+    fn parse_mem_binary_si_suffixes() {
+        assert_eq!(parse_mem("512Ki"), 512 * 1024);
+        assert_eq!(parse_mem("512Mi"), 512 * 1024 * 1024);
+        assert_eq!(parse_mem("1.5Gi"), 1_610_612_736);
+    }
+
+    #[test]
+    fn parse_mem_quoted_input() {
+        // the cpu-first capture patterns leave the memory capture quoted
+        assert_eq!(parse_mem("\"512Mi\""), 512 * 1024 * 1024);
+    }
+
+    #[test]
+    fn parse_mem_unknown_suffix_is_zero() {
+        assert_eq!(parse_mem("512Q"), 0);
+    }
+
+    #[test]
+    fn parse_mem_empty_is_zero() {
+        assert_eq!(parse_mem(""), 0);
+        assert_eq!(parse_mem("\"\""), 0);
+    }
+
+    #[test]
+    fn parse_cpu_bare_cores() {
+        assert_eq!(parse_cpu("2"), 2.0);
+        assert_eq!(parse_cpu("0.5"), 0.5);
+    }
+
+    #[test]
+    fn parse_cpu_millicores() {
+        assert_eq!(parse_cpu("500m"), 0.5);
+        assert_eq!(parse_cpu("100m"), 0.1);
+    }
+
+    #[test]
+    fn parse_cpu_unknown_suffix_is_zero() {
+        assert_eq!(parse_cpu("500x"), 0.0);
+    }
+
+    #[test]
+    fn parse_cpu_empty_is_zero() {
+        assert_eq!(parse_cpu(""), 0.0);
+    }
+
+    #[test]
+    fn analyze_sums_cpu_first_and_mem_first_orderings() {
+        init_regexes().unwrap();
+
+        let config = "
+            requests:
+                cpu: \"100m\"
+                memory: 128Mi
+            limits:
+                memory: \"512Mi\"
+                cpu: \"500m\"
+        ";
+
+        let resources = analyze(config);
+        assert_eq!(resources.cpu_request, 0.1);
+        assert_eq!(resources.mem_request, 128 * 1024 * 1024);
+        assert_eq!(resources.cpu_limit, 0.5);
+        assert_eq!(resources.mem_limit, 512 * 1024 * 1024);
+    }
 }